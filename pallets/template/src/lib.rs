@@ -8,7 +8,7 @@ pub mod pallet {
     use frame_support::pallet_prelude::*;
     use frame_system::pallet_prelude::*;
 
-    use frame_support::traits::{Currency, Randomness};
+    use frame_support::traits::{Currency, ExistenceRequirement, Randomness};
 
     // The basis which we buil
     #[pallet::pallet]
@@ -35,16 +35,35 @@ pub mod pallet {
         pub price: Option<BalanceOf<T>>,
         pub gender: Gender,
         pub owner: T::AccountId,
+        // The breeding generation; freshly created kitties are generation 0.
+        pub generation: u16,
     }
 
     /// Keeps track of the number of kitties in existence.
     #[pallet::storage]
     pub(super) type CountForKitties<T: Config> = StorageValue<_, u64, ValueQuery>;
 
+    /// Keeps track of the deepest lineage (highest generation) minted so far.
+    #[pallet::storage]
+    pub(super) type HighestGeneration<T: Config> = StorageValue<_, u16, ValueQuery>;
+
+    /// A monotonically increasing nonce folded into the DNA randomness payload,
+    /// so that each hashed payload is unique even within the same block.
+    #[pallet::storage]
+    pub(super) type Nonce<T: Config> = StorageValue<_, u64, ValueQuery>;
+
     /// Maps the kitty struct to the kitty DNA.
     #[pallet::storage]
     pub(super) type Kitties<T: Config> = StorageMap<_, Twox64Concat, [u8; 16], Kitty<T>>;
 
+    /// Enumerable index mapping a sequential position to a kitty's DNA.
+    #[pallet::storage]
+    pub(super) type AllKitties<T: Config> = StorageMap<_, Twox64Concat, u64, [u8; 16]>;
+
+    /// Reverse lookup from a kitty's DNA to its position in `AllKitties`.
+    #[pallet::storage]
+    pub(super) type AllKittiesIndex<T: Config> = StorageMap<_, Twox64Concat, [u8; 16], u64>;
+
     /// Track the kitties owned by each account.
     #[pallet::storage]
     pub(super) type KittiesOwned<T: Config> = StorageMap<
@@ -80,6 +99,14 @@ pub mod pallet {
     pub enum Event<T: Config> {
         /// A new kitty was successfully created.
         Created { kitty: [u8; 16], owner: T::AccountId },
+        /// A new kitty was successfully bred.
+        Bred { kitty: [u8; 16], owner: T::AccountId },
+        /// The price of a kitty was successfully set.
+        PriceSet { kitty: [u8; 16], price: Option<BalanceOf<T>> },
+        /// A kitty was successfully sold.
+        Sold { seller: T::AccountId, buyer: T::AccountId, kitty: [u8; 16], price: BalanceOf<T> },
+        /// A kitty was successfully transferred.
+        Transferred { from: T::AccountId, to: T::AccountId, kitty: [u8; 16] },
     }
 
     // Your Pallet's error messages.
@@ -91,6 +118,18 @@ pub mod pallet {
         DuplicateKitty,
         /// An overflow has occured!
         Overflow,
+        /// The caller does not own this kitty.
+        NotOwner,
+        /// Two kitties of the same gender cannot be bred together.
+        CantBreedSameGender,
+        /// This kitty is not for sale.
+        NotForSale,
+        /// The bid price was lower than the asking price.
+        BidPriceTooLow,
+        /// The buyer already owns this kitty.
+        BuyerIsOwner,
+        /// A kitty cannot be transferred to its current owner.
+        TransferToSelf,
     }
 
     // Your Pallet's callable functions.
@@ -105,10 +144,140 @@ pub mod pallet {
             let sender = ensure_signed(origin)?;
 
             // Generate unique DNA and Gender using a helper function
-            let (kitty_gen_dna, gender) = Self::gen_dna();
+            let (kitty_gen_dna, gender) = Self::gen_dna()?;
 
             // Write new kitty to storage by calling helper function
-            Self::mint(&sender, kitty_gen_dna, gender)?;
+            Self::mint(&sender, kitty_gen_dna, gender, 0)?;
+
+            Ok(())
+        }
+
+        /// Breed two owned kitties of opposite gender into a new kitty.
+        ///
+        /// The child DNA is a per-byte mix of the two parents, selected by a
+        /// fresh randomness mask. The actual kitty creation is done in the
+        /// `mint()` function.
+        #[pallet::weight(0)]
+        pub fn breed_kitty(
+            origin: OriginFor<T>,
+            parent1: [u8; 16],
+            parent2: [u8; 16],
+        ) -> DispatchResult {
+            // Make sure the caller is from a signed origin
+            let sender = ensure_signed(origin)?;
+
+            // Both parents must exist and be owned by the caller
+            let mom = Kitties::<T>::get(parent1).ok_or(Error::<T>::NotOwner)?;
+            let dad = Kitties::<T>::get(parent2).ok_or(Error::<T>::NotOwner)?;
+            ensure!(mom.owner == sender, Error::<T>::NotOwner);
+            ensure!(dad.owner == sender, Error::<T>::NotOwner);
+
+            // Breeding requires opposite genders
+            ensure!(mom.gender != dad.gender, Error::<T>::CantBreedSameGender);
+
+            // The child sits one generation below its deepest parent
+            let generation =
+                mom.generation.max(dad.generation).checked_add(1).ok_or(Error::<T>::Overflow)?;
+
+            // Mix the parents' DNA using a fresh randomness mask and mint the child
+            let (kitty_gen_dna, gender) = Self::breed_dna(&mom.dna, &dad.dna)?;
+            Self::mint(&sender, kitty_gen_dna, gender, generation)?;
+
+            // Deposit our "Bred" event.
+            Self::deposit_event(Event::Bred { kitty: kitty_gen_dna, owner: sender });
+
+            Ok(())
+        }
+
+        /// Set the price of an owned kitty.
+        ///
+        /// A price of `None` delists the kitty from the marketplace.
+        #[pallet::weight(0)]
+        pub fn set_price(
+            origin: OriginFor<T>,
+            kitty: [u8; 16],
+            new_price: Option<BalanceOf<T>>,
+        ) -> DispatchResult {
+            // Make sure the caller is from a signed origin
+            let sender = ensure_signed(origin)?;
+
+            // Ensure the kitty exists and is owned by the caller
+            let mut kitty_obj = Kitties::<T>::get(kitty).ok_or(Error::<T>::NotOwner)?;
+            ensure!(kitty_obj.owner == sender, Error::<T>::NotOwner);
+
+            // Set the price and write it back to storage
+            kitty_obj.price = new_price;
+            Kitties::<T>::insert(kitty, kitty_obj);
+
+            // Deposit a "PriceSet" event.
+            Self::deposit_event(Event::PriceSet { kitty, price: new_price });
+
+            Ok(())
+        }
+
+        /// Buy a kitty that is listed for sale for at most `max_price`.
+        #[pallet::weight(0)]
+        pub fn buy_kitty(
+            origin: OriginFor<T>,
+            kitty: [u8; 16],
+            max_price: BalanceOf<T>,
+        ) -> DispatchResult {
+            // Make sure the caller is from a signed origin
+            let buyer = ensure_signed(origin)?;
+
+            // Ensure the kitty exists and is listed for sale
+            let mut kitty_obj = Kitties::<T>::get(kitty).ok_or(Error::<T>::NotForSale)?;
+            let price = kitty_obj.price.ok_or(Error::<T>::NotForSale)?;
+
+            // The buyer cannot already own the kitty, and the bid must cover the price
+            let seller = kitty_obj.owner.clone();
+            ensure!(buyer != seller, Error::<T>::BuyerIsOwner);
+            ensure!(price <= max_price, Error::<T>::BidPriceTooLow);
+
+            // Transfer the balance from buyer to seller, keeping both accounts alive
+            T::Currency::transfer(&buyer, &seller, price, ExistenceRequirement::KeepAlive)?;
+
+            // Move the DNA between the two accounts' owned vectors
+            Self::do_transfer(&seller, &buyer, kitty)?;
+
+            // Update the stored kitty: new owner and delisted
+            kitty_obj.owner = buyer.clone();
+            kitty_obj.price = None;
+            Kitties::<T>::insert(kitty, kitty_obj);
+
+            // Deposit a "Sold" event.
+            Self::deposit_event(Event::Sold { seller, buyer, kitty, price });
+
+            Ok(())
+        }
+
+        /// Transfer an owned kitty to another account.
+        #[pallet::weight(0)]
+        pub fn transfer_kitty(
+            origin: OriginFor<T>,
+            to: T::AccountId,
+            kitty: [u8; 16],
+        ) -> DispatchResult {
+            // Make sure the caller is from a signed origin
+            let from = ensure_signed(origin)?;
+
+            // Ensure the kitty exists and is owned by the caller
+            let mut kitty_obj = Kitties::<T>::get(kitty).ok_or(Error::<T>::NotOwner)?;
+            ensure!(kitty_obj.owner == from, Error::<T>::NotOwner);
+
+            // A kitty cannot be transferred to its current owner
+            ensure!(from != to, Error::<T>::TransferToSelf);
+
+            // Move the DNA between the two accounts' owned vectors
+            Self::do_transfer(&from, &to, kitty)?;
+
+            // Update the stored kitty: new owner and delisted
+            kitty_obj.owner = to.clone();
+            kitty_obj.price = None;
+            Kitties::<T>::insert(kitty, kitty_obj);
+
+            // Deposit a "Transferred" event.
+            Self::deposit_event(Event::Transferred { from, to, kitty });
 
             Ok(())
         }
@@ -117,38 +286,123 @@ pub mod pallet {
     // Your Pallet's internal functions.
     impl<T: Config> Pallet<T> {
         // Generates and returns DNA and Gender
-        fn gen_dna() -> ([u8; 16], Gender) {
+        fn gen_dna() -> Result<([u8; 16], Gender), DispatchError> {
             // Create randomness
             let random = T::KittyRandomness::random(&b"dna"[..]).0;
 
+            // Read the current nonce and advance it so the hashed payload is unique
+            // even for multiple kitties created in the same block.
+            let nonce = Nonce::<T>::get();
+            let next_nonce = nonce.checked_add(1).ok_or(Error::<T>::Overflow)?;
+
             // Create randomness payload. Multiple kitties can be generated in the same block,
             // retaining uniqueness.
             let unique_payload = (
                 random,
                 frame_system::Pallet::<T>::extrinsic_index().unwrap_or_default(),
                 frame_system::Pallet::<T>::block_number(),
+                nonce,
             );
 
             // Turns into a byte array
             let encoded_payload = unique_payload.encode();
             let hash = frame_support::Hashable::blake2_128(&encoded_payload);
 
+            // Persist the advanced nonce now that the payload has been built
+            Nonce::<T>::put(next_nonce);
+
             // Generate Gender
             if hash[0] % 2 == 0 {
-                (hash, Gender::Male)
+                Ok((hash, Gender::Male))
             } else {
-                (hash, Gender::Female)
+                Ok((hash, Gender::Female))
             }
         }
 
+        // Mixes two parents' DNA into a child's DNA and derives its Gender
+        fn breed_dna(
+            parent1: &[u8; 16],
+            parent2: &[u8; 16],
+        ) -> Result<([u8; 16], Gender), DispatchError> {
+            // Create randomness
+            let random = T::KittyRandomness::random(&b"dna"[..]).0;
+
+            // Read the current nonce and advance it so the selection mask is unique
+            // even for multiple kitties bred in the same block.
+            let nonce = Nonce::<T>::get();
+            let next_nonce = nonce.checked_add(1).ok_or(Error::<T>::Overflow)?;
+
+            // Create randomness payload. Multiple kitties can be bred in the same
+            // block, retaining uniqueness.
+            let unique_payload = (
+                random,
+                frame_system::Pallet::<T>::extrinsic_index().unwrap_or_default(),
+                frame_system::Pallet::<T>::block_number(),
+                nonce,
+            );
+
+            // Turns into a byte array; its first 16 bits (bytes 0 and 1) form the
+            // selection mask
+            let encoded_payload = unique_payload.encode();
+            let r = frame_support::Hashable::blake2_128(&encoded_payload);
+
+            // Persist the advanced nonce now that the payload has been built
+            Nonce::<T>::put(next_nonce);
+
+            // For each byte, take it from `parent2` when the matching mask bit is
+            // set, otherwise from `parent1`.
+            let mut dna = [0u8; 16];
+            for i in 0..16 {
+                dna[i] = if r[i / 8] & (1 << (i % 8)) == 0 {
+                    parent1[i]
+                } else {
+                    parent2[i]
+                };
+            }
+
+            // Generate Gender
+            if dna[0] % 2 == 0 {
+                Ok((dna, Gender::Male))
+            } else {
+                Ok((dna, Gender::Female))
+            }
+        }
+
+        // Moves a kitty's DNA from one account's owned vector to another's.
+        fn do_transfer(
+            from: &T::AccountId,
+            to: &T::AccountId,
+            kitty: [u8; 16],
+        ) -> DispatchResult {
+            // Remove the DNA from the sender's owned vector
+            let mut from_owned = KittiesOwned::<T>::get(from);
+            if let Some(idx) = from_owned.iter().position(|owned| *owned == kitty) {
+                from_owned.swap_remove(idx);
+            } else {
+                return Err(Error::<T>::NotOwner.into());
+            }
+
+            // Append the DNA to the recipient's owned vector
+            let mut to_owned = KittiesOwned::<T>::get(to);
+            to_owned.try_push(kitty).map_err(|_| Error::<T>::TooManyOwned)?;
+
+            // Write the updated vectors back to storage
+            KittiesOwned::<T>::insert(from, from_owned);
+            KittiesOwned::<T>::insert(to, to_owned);
+
+            Ok(())
+        }
+
         // Helper to mint a kitty
         pub fn mint(
             owner: &T::AccountId,
             dna: [u8; 16],
             gender: Gender,
+            generation: u16,
         ) -> Result<[u8; 16], DispatchError> {
             // Create a new object
-            let kitty = Kitty::<T> { dna, price: None, gender, owner: owner.clone() };
+            let kitty =
+                Kitty::<T> { dna, price: None, gender, owner: owner.clone(), generation };
 
             // Check if the kitty does not already exist in our storage map
             ensure!(!Kitties::<T>::contains_key(&kitty.dna), Error::<T>::DuplicateKitty);
@@ -163,8 +417,18 @@ pub mod pallet {
 
             // Write new kitty to storage
             Kitties::<T>::insert(kitty.dna, kitty);
+
+            // Append to the global enumerable index at the current count position
+            AllKitties::<T>::insert(count, dna);
+            AllKittiesIndex::<T>::insert(dna, count);
+
             CountForKitties::<T>::put(new_count);
 
+            // Record the deepest lineage seen so far
+            if generation > HighestGeneration::<T>::get() {
+                HighestGeneration::<T>::put(generation);
+            }
+
             // Deposit our "Created" event.
             Self::deposit_event(Event::Created { kitty: dna, owner: owner.clone() });
 